@@ -1,17 +1,29 @@
 use anyhow::{bail, Result};
-use bollard::Docker;
+use bollard::{
+    auth::DockerCredentials,
+    errors::Error as DockerError,
+    image::CreateImageOptions,
+    Docker,
+};
 use futures_util::{future::ready, StreamExt};
-use log::{debug, error};
+use log::{debug, error, info};
 use serde::Deserialize;
+use sha2::{Digest as _, Sha256, Sha384, Sha512};
 use std::{
+    cell::RefCell,
+    collections::HashMap,
     fs::{self, File},
-    io::{BufReader, BufWriter, Write},
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
     os::unix::prelude::PermissionsExt,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
+    rc::Rc,
 };
 use structopt::StructOpt;
 use tar::Archive;
 
+/// Docker's legacy default registry key, used in `~/.docker/config.json` for Docker Hub.
+const DEFAULT_REGISTRY: &str = "https://index.docker.io/v1/";
+
 #[derive(Debug, StructOpt)]
 #[structopt(
     name = "dext",
@@ -19,7 +31,8 @@ use tar::Archive;
     author = env!("CARGO_PKG_AUTHORS"),
 )]
 struct Opts {
-    /// Docker image name
+    /// Docker image name. Accepts a `name@sha256:...` digest reference in place of a plain
+    /// name, in which case --version is ignored.
     #[structopt(short = "i", long = "image")]
     image_name: Option<String>,
 
@@ -28,7 +41,8 @@ struct Opts {
     #[structopt(short = "v", long = "version", default_value = "latest")]
     image_version: String,
 
-    /// Image archive file (.tar)
+    /// Image archive file (.tar), or a directory already unpacked into either the legacy
+    /// `docker save` layout or an OCI image-layout (index.json + blobs/).
     #[structopt(short = "f", long = "file")]
     image_file: Option<PathBuf>,
 
@@ -45,6 +59,24 @@ struct Opts {
     /// Entrypoint file name, relative to out_path.
     #[structopt(long = "entry-file", default_value = "entrypoint.sh")]
     entrypoint: String,
+
+    /// Registry username, used to pull the image if it isn't present locally.
+    // Falls back to the matching entry in ~/.docker/config.json when not set.
+    #[structopt(short = "u", long = "username")]
+    username: Option<String>,
+
+    /// Registry password, used alongside --username.
+    #[structopt(long = "password")]
+    password: Option<String>,
+
+    /// Verify each layer and the image config against the digests named in the manifest.
+    #[structopt(long = "verify")]
+    verify: bool,
+
+    /// Entrypoint output format: `script` (a runnable shell script, the default), `env` (a
+    /// sourceable file of exported variables), or `json` (a summary of the effective command).
+    #[structopt(long = "format", default_value = "script")]
+    entry_format: EntrypointFormat,
 }
 
 #[tokio::main]
@@ -65,13 +97,23 @@ async fn main() -> Result<()> {
     let tar_path = {
         match (opts.image_name, opts.image_file) {
             (Some(image_name), None) => {
-                if image_name.contains(":") {
-                    bail!("image name should be the name only - use the --version flag to specify a version.");
-                }
-
-                let image = format!("{}:{}", image_name, opts.image_version);
+                let image = if image_name.contains('@') {
+                    // A `name@sha256:...` digest reference - already a full reference, so pass
+                    // it through as-is rather than appending --version's tag.
+                    image_name
+                } else if image_name
+                    .rsplit_once('/')
+                    .unwrap_or(("", &image_name))
+                    .1
+                    .contains(':')
+                {
+                    bail!("image name should be the name only - use the --version flag to specify a version, or pass a name@digest reference.");
+                } else {
+                    format!("{}:{}", image_name, opts.image_version)
+                };
 
-                fetch_archive(tmp.path(), &image).await?
+                fetch_archive(tmp.path(), &image, opts.username.as_deref(), opts.password.as_deref())
+                    .await?
             }
             (None, Some(tar_path)) => tar_path,
             (Some(_), Some(_)) => {
@@ -84,16 +126,29 @@ async fn main() -> Result<()> {
             }
         }
     };
-    let manifest = extract_layers(&tar_path, &opts.out_path, tmp.path()).await?;
+    let (manifest, layout_dir) =
+        extract_layers(&tar_path, &opts.out_path, tmp.path(), opts.verify).await?;
 
     if opts.write_entrypoint {
-        write_entrypoint(&manifest, tmp.path(), &opts.out_path, opts.entrypoint)?;
+        write_entrypoint(
+            &manifest,
+            &layout_dir,
+            &opts.out_path,
+            opts.entrypoint,
+            opts.verify,
+            opts.entry_format,
+        )?;
     }
 
     Ok(())
 }
 
-async fn fetch_archive(tmp: &Path, image: &str) -> Result<PathBuf> {
+async fn fetch_archive(
+    tmp: &Path,
+    image: &str,
+    username: Option<&str>,
+    password: Option<&str>,
+) -> Result<PathBuf> {
     let tar_name = format!("{image}.tar");
     let mut tar_path = PathBuf::new();
     tar_path.push(&tmp);
@@ -101,8 +156,15 @@ async fn fetch_archive(tmp: &Path, image: &str) -> Result<PathBuf> {
     debug!("tar file: {}", tar_path.to_string_lossy());
 
     let docker = Docker::connect_with_local_defaults()?;
-    // Make sure the image is there.
-    docker.inspect_image(image).await?;
+    // Make sure the image is there, pulling it first if it's missing.
+    if let Err(e) = docker.inspect_image(image).await {
+        if !is_not_found(&e) {
+            return Err(e.into());
+        }
+
+        debug!("image not present locally, pulling: {image}");
+        pull_image(&docker, image, username, password).await?;
+    }
 
     let byte_stream = docker.export_image(image);
 
@@ -121,32 +183,655 @@ async fn fetch_archive(tmp: &Path, image: &str) -> Result<PathBuf> {
     Ok(tar_path)
 }
 
-async fn extract_layers(tar_path: &Path, out_path: &Path, tmp: &Path) -> Result<Manifest> {
-    let reader = BufReader::new(File::open(tar_path)?);
-    let mut archive = Archive::new(reader);
-    debug!("unpacking archive: {}", tar_path.to_string_lossy());
-    archive.unpack(&tmp)?;
+fn is_not_found(e: &DockerError) -> bool {
+    matches!(e, DockerError::DockerResponseServerError { status_code, .. } if *status_code == 404)
+}
+
+/// Pulls `image` (a `name:tag` or `name@digest` reference) into the local daemon, streaming
+/// progress to the log, mirroring bollard's `create_image` / `CreateImageOptions` flow.
+async fn pull_image(
+    docker: &Docker,
+    image: &str,
+    username: Option<&str>,
+    password: Option<&str>,
+) -> Result<()> {
+    let (from_image, tag) = match image.split_once('@') {
+        Some((name, digest)) => (name, digest),
+        None => image.rsplit_once(':').unwrap_or((image, "latest")),
+    };
+
+    let options = CreateImageOptions {
+        from_image,
+        tag,
+        ..Default::default()
+    };
+
+    let credentials = docker_credentials(from_image, username, password)?;
+
+    let mut pull_stream = docker.create_image(Some(options), None, credentials);
+    while let Some(progress) = pull_stream.next().await {
+        let info = progress?;
+        match (&info.status, &info.progress) {
+            (Some(status), Some(progress)) => info!("{status}: {progress}"),
+            (Some(status), None) => info!("{status}"),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Resolves credentials for `image_name`'s registry, preferring explicit `--username`/
+/// `--password` flags and falling back to the matching entry in `~/.docker/config.json`.
+fn docker_credentials(
+    image_name: &str,
+    username: Option<&str>,
+    password: Option<&str>,
+) -> Result<Option<DockerCredentials>> {
+    if let (Some(username), Some(password)) = (username, password) {
+        return Ok(Some(DockerCredentials {
+            username: Some(username.to_string()),
+            password: Some(password.to_string()),
+            ..Default::default()
+        }));
+    }
+
+    let registry = registry_for(image_name);
+    read_docker_config_auth(&registry)
+}
+
+/// Extracts the registry host from an image name, e.g. `registry.example.com/foo/bar` -> that
+/// host, `library/ubuntu` or `ubuntu` -> Docker Hub's legacy config key.
+fn registry_for(image_name: &str) -> String {
+    match image_name.split_once('/') {
+        Some((first, _)) if first.contains('.') || first.contains(':') || first == "localhost" => {
+            first.to_string()
+        }
+        _ => DEFAULT_REGISTRY.to_string(),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct DockerConfigFile {
+    #[serde(default)]
+    auths: HashMap<String, DockerAuthEntry>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DockerAuthEntry {
+    auth: Option<String>,
+}
+
+fn read_docker_config_auth(registry: &str) -> Result<Option<DockerCredentials>> {
+    let config_path = match dirs_next::home_dir() {
+        Some(home) => home.join(".docker").join("config.json"),
+        None => return Ok(None),
+    };
+
+    if !config_path.is_file() {
+        return Ok(None);
+    }
+
+    debug!("reading registry credentials from: {}", config_path.to_string_lossy());
+    let file = BufReader::new(File::open(&config_path)?);
+    let config: DockerConfigFile = serde_json::from_reader(file)?;
+
+    let entry = match config.auths.get(registry) {
+        Some(entry) => entry,
+        None => return Ok(None),
+    };
+
+    let auth = match &entry.auth {
+        Some(auth) => auth,
+        None => return Ok(None),
+    };
+
+    let decoded = String::from_utf8(base64::decode(auth)?)?;
+    let (username, password) = decoded
+        .split_once(':')
+        .ok_or_else(|| anyhow::anyhow!("malformed auth entry for registry {registry}"))?;
+
+    Ok(Some(DockerCredentials {
+        username: Some(username.to_string()),
+        password: Some(password.to_string()),
+        serveraddress: Some(registry.to_string()),
+        ..Default::default()
+    }))
+}
 
-    fs::remove_file(&tar_path)?;
+async fn extract_layers(
+    tar_path: &Path,
+    out_path: &Path,
+    tmp: &Path,
+    verify: bool,
+) -> Result<(Manifest, PathBuf)> {
+    let layout_dir = if tar_path.is_dir() {
+        debug!("using directory as image source: {}", tar_path.to_string_lossy());
+        tar_path.to_path_buf()
+    } else {
+        let reader = BufReader::new(File::open(tar_path)?);
+        let mut archive = Archive::new(reader);
+        debug!("unpacking archive: {}", tar_path.to_string_lossy());
+        archive.unpack(&tmp)?;
+        fs::remove_file(&tar_path)?;
+        tmp.to_path_buf()
+    };
 
     let mut mf_path = PathBuf::new();
-    mf_path.push(&tmp);
+    mf_path.push(&layout_dir);
     mf_path.push("manifest.json");
 
-    let manifest = read_manifest(&File::open(mf_path)?)?;
+    let manifest = if mf_path.is_file() {
+        read_manifest(&File::open(&mf_path)?)?
+    } else if layout_dir.join("index.json").is_file() {
+        debug!("manifest.json not found, resolving OCI image-layout via index.json");
+        resolve_oci_manifest(&layout_dir)?
+    } else {
+        bail!("neither manifest.json nor an OCI index.json was found in the image source");
+    };
     debug!("read manifest and found {} layers", manifest.layers.len());
 
+    let mut verified = 0;
     for layer in manifest.layers.iter() {
         let mut layer_path = PathBuf::new();
-        layer_path.push(&tmp);
+        layer_path.push(&layout_dir);
         layer_path.push(layer);
-        let reader = BufReader::new(File::open(&layer_path)?);
-        let mut archive = Archive::new(reader);
+        let mut reader = BufReader::new(File::open(&layer_path)?);
         debug!("unpacking layer: {}", layer_path.to_string_lossy());
-        archive.unpack(out_path)?;
+
+        let compression = Compression::sniff(reader.fill_buf()?);
+        debug!("layer {layer} compression: {compression:?}");
+
+        let digest_handle = verify
+            .then(|| BlobDigest::from_blob_name(layer))
+            .transpose()?
+            .map(|digest| {
+                let handle = DigestHandle::new(digest.algo);
+                (digest, handle)
+            });
+
+        let source: Box<dyn Read> = match &digest_handle {
+            Some((_, handle)) => Box::new(DigestReader::new(reader, handle.clone())),
+            None => Box::new(reader),
+        };
+
+        let mut archive = Archive::new(compression.decode(source)?);
+        unpack_layer(&mut archive, out_path)?;
+        // `tar::Archive` stops at the first end-of-archive block, so read out whatever is left
+        // of the blob; otherwise the digest would cover only a prefix of it.
+        io::copy(&mut archive.into_inner(), &mut io::sink())?;
+
+        if let Some((digest, handle)) = digest_handle {
+            digest.check(handle.finalize_hex(), layer)?;
+            verified += 1;
+        }
     }
 
-    Ok(manifest)
+    if verify {
+        info!("verified {verified} layer digest(s) against the manifest");
+    }
+
+    Ok((manifest, layout_dir))
+}
+
+/// Resolves a `manifest.json`-equivalent from an OCI image-layout directory: reads `index.json`,
+/// selects the entry matching the host platform (or the sole entry, if there's only one), and
+/// follows it - recursing through nested manifest lists - down to a single image manifest.
+fn resolve_oci_manifest(layout_dir: &Path) -> Result<Manifest> {
+    let index_path = layout_dir.join("index.json");
+    debug!("reading OCI image index: {}", index_path.to_string_lossy());
+    let index: OciIndex = serde_json::from_reader(BufReader::new(File::open(&index_path)?))?;
+
+    let descriptor = select_platform_descriptor(&index.manifests)?;
+    resolve_oci_descriptor(layout_dir, descriptor)
+}
+
+fn resolve_oci_descriptor(layout_dir: &Path, descriptor: &OciDescriptor) -> Result<Manifest> {
+    let blob_path = layout_dir.join(oci_blob_path(&descriptor.digest)?);
+    debug!("reading OCI manifest blob: {}", blob_path.to_string_lossy());
+    let parsed: OciManifestOrIndex =
+        serde_json::from_reader(BufReader::new(File::open(&blob_path)?))?;
+
+    match parsed {
+        OciManifestOrIndex::Index(nested) => {
+            let descriptor = select_platform_descriptor(&nested.manifests)?;
+            resolve_oci_descriptor(layout_dir, descriptor)
+        }
+        OciManifestOrIndex::Manifest(manifest) => Ok(Manifest {
+            config: oci_blob_path(&manifest.config.digest)?,
+            layers: manifest
+                .layers
+                .iter()
+                .map(|layer| oci_blob_path(&layer.digest))
+                .collect::<Result<Vec<_>>>()?,
+        }),
+    }
+}
+
+fn select_platform_descriptor(manifests: &[OciDescriptor]) -> Result<&OciDescriptor> {
+    if let [only] = manifests {
+        return Ok(only);
+    }
+
+    let os = goos();
+    let arch = goarch();
+
+    manifests
+        .iter()
+        .find(|descriptor| {
+            descriptor
+                .platform
+                .as_ref()
+                .is_some_and(|platform| platform.os == os && platform.architecture == arch)
+        })
+        .ok_or_else(|| anyhow::anyhow!("no manifest in the image index matches platform {os}/{arch}"))
+}
+
+/// Maps Rust's `std::env::consts::OS` to the GOOS-style string OCI image indexes use, e.g.
+/// `"macos"` -> `"darwin"`.
+fn goos() -> &'static str {
+    match std::env::consts::OS {
+        "macos" => "darwin",
+        other => other,
+    }
+}
+
+/// Maps Rust's `std::env::consts::ARCH` to the GOARCH-style string OCI image indexes use, e.g.
+/// `"x86_64"` -> `"amd64"`, `"aarch64"` -> `"arm64"`.
+fn goarch() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        other => other,
+    }
+}
+
+/// Maps a `sha256:<hex>`-style digest to its path within an OCI image-layout, e.g.
+/// `blobs/sha256/<hex>`.
+fn oci_blob_path(digest: &str) -> Result<String> {
+    let (algo, hex) = digest
+        .split_once(':')
+        .ok_or_else(|| anyhow::anyhow!("malformed digest: {digest}"))?;
+    if hex.is_empty() || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        bail!("malformed digest: {digest}");
+    }
+    Ok(format!("blobs/{algo}/{hex}"))
+}
+
+#[derive(Deserialize, Debug)]
+struct OciIndex {
+    manifests: Vec<OciDescriptor>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OciDescriptor {
+    digest: String,
+    #[serde(default)]
+    platform: Option<OciPlatform>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OciPlatform {
+    architecture: String,
+    os: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum OciManifestOrIndex {
+    Index(OciIndex),
+    Manifest(OciManifest),
+}
+
+#[derive(Deserialize, Debug)]
+struct OciManifest {
+    config: OciDescriptor,
+    layers: Vec<OciDescriptor>,
+}
+
+/// Marks a deleted path when unioning OCI/Docker layers: the basename `.wh.<name>` records
+/// that `<name>` was removed in this layer, and `.wh..wh..opq` marks a directory whose entire
+/// prior (lower-layer) contents are replaced by this layer's.
+const WHITEOUT_PREFIX: &str = ".wh.";
+const OPAQUE_WHITEOUT_NAME: &str = ".wh..wh..opq";
+
+/// What a tar entry's path means for the flattened rootfs: a normal entry to extract as-is, or
+/// one of the two OCI whiteout markers naming the directory they act on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum WhiteoutAction {
+    Extract,
+    ClearDirectory(PathBuf),
+    Remove(PathBuf),
+}
+
+/// Classifies a tar entry's path as a normal extraction or a whiteout marker, purely from the
+/// path itself - no I/O, so the unioning rule can be unit-tested without building an archive.
+fn classify_whiteout(entry_path: &Path) -> WhiteoutAction {
+    let file_name = entry_path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    let parent = entry_path.parent().map(Path::to_path_buf).unwrap_or_default();
+
+    if file_name == OPAQUE_WHITEOUT_NAME {
+        return WhiteoutAction::ClearDirectory(parent);
+    }
+
+    if let Some(name) = file_name.strip_prefix(WHITEOUT_PREFIX) {
+        return WhiteoutAction::Remove(parent.join(name));
+    }
+
+    WhiteoutAction::Extract
+}
+
+/// Resolves `relative` (a whiteout's target path, derived from a tar entry's name) against
+/// `out_path`, rejecting any component that could escape it. `entry.unpack_in` gives normal
+/// entries this same guarantee for free; whiteouts bypass `unpack_in` since they're never
+/// extracted, so they need the same treatment applied explicitly before anything destructive
+/// happens. A layer's entry names are attacker-controlled input (the image may have been pulled
+/// from a remote registry), so neither a leading `/` nor a `..` component can be trusted to mean
+/// what it says - `Path::join` would otherwise let the former discard `out_path` entirely and
+/// the latter walk back out of it.
+fn resolve_within(out_path: &Path, relative: &Path) -> Result<PathBuf> {
+    let mut resolved = out_path.to_path_buf();
+    for component in relative.components() {
+        match component {
+            Component::Normal(part) => resolved.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                bail!(
+                    "refusing to apply whiteout outside the output directory: {}",
+                    relative.to_string_lossy()
+                );
+            }
+        }
+    }
+    Ok(resolved)
+}
+
+/// Unpacks `archive`'s entries into `out_path` one at a time, applying OCI whiteouts instead of
+/// extracting the marker entries themselves, so the result is a correctly flattened rootfs
+/// rather than one where every layer's deletions are silently ignored.
+fn unpack_layer<R: Read>(archive: &mut Archive<R>, out_path: &Path) -> Result<()> {
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_path = entry.path()?.into_owned();
+
+        match classify_whiteout(&entry_path) {
+            WhiteoutAction::ClearDirectory(parent) => {
+                let target = resolve_within(out_path, &parent)?;
+                debug!("opaque whiteout: clearing {}", target.to_string_lossy());
+                clear_directory(&target)?;
+                continue;
+            }
+            WhiteoutAction::Remove(target) => {
+                let target = resolve_within(out_path, &target)?;
+                debug!("whiteout: removing {}", target.to_string_lossy());
+                remove_whiteout_target(&target)?;
+                continue;
+            }
+            WhiteoutAction::Extract => {}
+        }
+
+        entry.unpack_in(out_path)?;
+    }
+
+    Ok(())
+}
+
+/// Removes every entry from a directory that earlier layers populated, as directed by an
+/// opaque whiteout. A no-op if the directory doesn't exist yet (nothing to clear).
+fn clear_directory(dir: &Path) -> Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            fs::remove_dir_all(&path)?;
+        } else {
+            fs::remove_file(&path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes the file or directory a whiteout marker targets. A no-op if it was never actually
+/// extracted (e.g. the whiteout and the thing it deletes both live in the base layer's image,
+/// which dext never saw materialize).
+fn remove_whiteout_target(path: &Path) -> Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(path)?,
+        Ok(_) => fs::remove_file(path)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    Ok(())
+}
+
+/// The digest algorithm a manifest can reference a blob by, inferred from the hex digest's
+/// length (as sha256/sha384/sha512 don't otherwise appear in a legacy `manifest.json`).
+#[derive(Debug, Clone, Copy)]
+enum DigestAlgo {
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl DigestAlgo {
+    fn from_hex_len(len: usize) -> Option<Self> {
+        match len {
+            64 => Some(Self::Sha256),
+            96 => Some(Self::Sha384),
+            128 => Some(Self::Sha512),
+            _ => None,
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sha256" => Some(Self::Sha256),
+            "sha384" => Some(Self::Sha384),
+            "sha512" => Some(Self::Sha512),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            Self::Sha384 => "sha384",
+            Self::Sha512 => "sha512",
+        }
+    }
+}
+
+/// The expected digest for a blob, derived from its path/name within the extracted archive,
+/// e.g. `<hash>/layer.tar` or `<hash>.json`.
+struct BlobDigest {
+    algo: DigestAlgo,
+    expected_hex: String,
+}
+
+impl BlobDigest {
+    fn from_blob_name(blob_path: &str) -> Result<Self> {
+        let path = Path::new(blob_path);
+
+        // OCI image-layout blobs are stored as `blobs/<algo>/<hex-digest>` — the algorithm is
+        // the immediate parent directory, and the filename itself is the hash.
+        let oci_layout = path
+            .parent()
+            .and_then(|parent| parent.file_name())
+            .and_then(|name| name.to_str())
+            .and_then(DigestAlgo::from_name)
+            .zip(path.file_name().and_then(|name| name.to_str()));
+
+        if let Some((algo, hex)) = oci_layout {
+            return Ok(Self {
+                algo,
+                expected_hex: hex.to_lowercase(),
+            });
+        }
+
+        // Legacy `docker save` layout: `<hash>/layer.tar` or `<hash>.json`.
+        let candidate = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .and_then(|parent| parent.components().next())
+            .map(|component| component.as_os_str().to_string_lossy().to_string())
+            .or_else(|| {
+                path.file_stem()
+                    .map(|stem| stem.to_string_lossy().to_string())
+            })
+            .ok_or_else(|| anyhow::anyhow!("cannot determine a digest name from {blob_path}"))?;
+
+        let algo = DigestAlgo::from_hex_len(candidate.len()).ok_or_else(|| {
+            anyhow::anyhow!("cannot determine digest algorithm for blob: {blob_path}")
+        })?;
+
+        Ok(Self {
+            algo,
+            expected_hex: candidate.to_lowercase(),
+        })
+    }
+
+    fn check(&self, actual_hex: String, blob_path: &str) -> Result<()> {
+        if actual_hex != self.expected_hex {
+            bail!(
+                "{} digest mismatch for {blob_path}: expected {}, got {actual_hex}",
+                self.algo.name(),
+                self.expected_hex
+            );
+        }
+        debug!("verified {}:{} for {blob_path}", self.algo.name(), self.expected_hex);
+        Ok(())
+    }
+}
+
+/// A handle onto an in-progress digest, shared between the `DigestReader` that feeds raw blob
+/// bytes into it and the caller that reads back the final hash once extraction has finished.
+/// The blob is commonly re-wrapped in a decompressing reader before reaching `tar::Archive`, so
+/// ownership can't simply flow back through `Archive::into_inner` once extraction is done.
+#[derive(Clone)]
+struct DigestHandle(Rc<RefCell<BlobHasher>>);
+
+impl DigestHandle {
+    fn new(algo: DigestAlgo) -> Self {
+        Self(Rc::new(RefCell::new(BlobHasher::new(algo))))
+    }
+
+    fn update(&self, data: &[u8]) {
+        self.0.borrow_mut().update(data);
+    }
+
+    fn finalize_hex(self) -> String {
+        Rc::try_unwrap(self.0)
+            .unwrap_or_else(|_| panic!("digest handle still in use"))
+            .into_inner()
+            .finalize_hex()
+    }
+}
+
+/// A `Read` wrapper that incrementally hashes every byte that passes through it, so a digest
+/// can be verified as a blob streams into `tar::Archive` without buffering it in memory.
+struct DigestReader<R> {
+    inner: R,
+    handle: DigestHandle,
+}
+
+impl<R: Read> DigestReader<R> {
+    fn new(inner: R, handle: DigestHandle) -> Self {
+        Self { inner, handle }
+    }
+}
+
+impl<R: Read> Read for DigestReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            self.handle.update(&buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+enum BlobHasher {
+    Sha256(Sha256),
+    Sha384(Sha384),
+    Sha512(Sha512),
+}
+
+impl BlobHasher {
+    fn new(algo: DigestAlgo) -> Self {
+        match algo {
+            DigestAlgo::Sha256 => Self::Sha256(Sha256::new()),
+            DigestAlgo::Sha384 => Self::Sha384(Sha384::new()),
+            DigestAlgo::Sha512 => Self::Sha512(Sha512::new()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Sha256(h) => h.update(data),
+            Self::Sha384(h) => h.update(data),
+            Self::Sha512(h) => h.update(data),
+        }
+    }
+
+    fn finalize_hex(self) -> String {
+        match self {
+            Self::Sha256(h) => hex::encode(h.finalize()),
+            Self::Sha384(h) => hex::encode(h.finalize()),
+            Self::Sha512(h) => hex::encode(h.finalize()),
+        }
+    }
+}
+
+/// A layer's compression, sniffed from its leading magic bytes so callers don't need the
+/// config's media type to know how to decode it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Xz,
+    Bzip2,
+}
+
+impl Compression {
+    fn sniff(leading_bytes: &[u8]) -> Self {
+        if leading_bytes.starts_with(&[0x1f, 0x8b]) {
+            Self::Gzip
+        } else if leading_bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Self::Zstd
+        } else if leading_bytes.len() >= 6
+            && leading_bytes[0] == 0xfd
+            && &leading_bytes[1..5] == b"7zXZ"
+            && leading_bytes[5] == 0x00
+        {
+            Self::Xz
+        } else if leading_bytes.starts_with(b"BZh") {
+            Self::Bzip2
+        } else {
+            Self::None
+        }
+    }
+
+    /// Wraps `reader` in the matching streaming decoder, so the caller gets back plain
+    /// uncompressed tar bytes regardless of what was sniffed.
+    fn decode<'a, R: Read + 'a>(self, reader: R) -> Result<Box<dyn Read + 'a>> {
+        Ok(match self {
+            Self::None => Box::new(reader),
+            Self::Gzip => Box::new(flate2::read::GzDecoder::new(reader)),
+            Self::Zstd => Box::new(zstd::stream::read::Decoder::new(reader)?),
+            Self::Xz => Box::new(xz2::read::XzDecoder::new(reader)),
+            Self::Bzip2 => Box::new(bzip2::read::BzDecoder::new(reader)),
+        })
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -183,15 +868,33 @@ struct ImageConfig {
     config: Config,
 }
 
-#[derive(Deserialize, Debug)]
-
+#[derive(Deserialize, Debug, Default)]
 struct Config {
-    #[serde(alias = "Env")]
+    #[serde(alias = "Env", default)]
     env: Vec<String>,
-    #[serde(alias = "Cmd")]
-    cmd: Vec<String>,
-    #[serde(alias = "WorkingDir")]
+    #[serde(alias = "Cmd", default)]
+    cmd: Option<Vec<String>>,
+    #[serde(alias = "Entrypoint", default)]
+    entrypoint: Option<Vec<String>>,
+    #[serde(alias = "WorkingDir", default)]
     working_dir: String,
+    #[serde(alias = "User", default)]
+    user: String,
+    #[serde(alias = "ExposedPorts", default)]
+    exposed_ports: HashMap<String, serde_json::Value>,
+}
+
+impl Config {
+    /// The effective command a container runtime would run: `Entrypoint` followed by `Cmd`,
+    /// the way `docker run` concatenates them when both are set.
+    fn run_command(&self) -> Vec<String> {
+        self.entrypoint
+            .iter()
+            .flatten()
+            .chain(self.cmd.iter().flatten())
+            .cloned()
+            .collect()
+    }
 }
 
 fn read_config(config: &File) -> Result<ImageConfig> {
@@ -199,11 +902,37 @@ fn read_config(config: &File) -> Result<ImageConfig> {
     Ok(serde_json::from_reader(config)?)
 }
 
+/// How `write_entrypoint` should render the image's effective run command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EntrypointFormat {
+    /// A runnable `#!/bin/bash` script (the default).
+    Script,
+    /// A sourceable file of `export KEY=value` lines.
+    Env,
+    /// A JSON summary of the effective command, env, user and working directory.
+    Json,
+}
+
+impl std::str::FromStr for EntrypointFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "script" => Ok(Self::Script),
+            "env" => Ok(Self::Env),
+            "json" => Ok(Self::Json),
+            other => bail!("unknown --format value: {other} (expected script, env, or json)"),
+        }
+    }
+}
+
 fn write_entrypoint(
     manifest: &Manifest,
     tmp: &Path,
     out_path: &Path,
     entrypoint: String,
+    verify: bool,
+    format: EntrypointFormat,
 ) -> Result<()> {
     let mut cfg = PathBuf::new();
     cfg.push(&tmp);
@@ -212,7 +941,19 @@ fn write_entrypoint(
         "reading image configuration from: {}",
         cfg.to_string_lossy()
     );
-    let config = read_config(&File::open(cfg)?)?;
+
+    if verify {
+        let digest = BlobDigest::from_blob_name(&manifest.config)?;
+        let handle = DigestHandle::new(digest.algo);
+        let mut reader = DigestReader::new(BufReader::new(File::open(&cfg)?), handle.clone());
+        let mut contents = Vec::new();
+        reader.read_to_end(&mut contents)?;
+        drop(reader);
+        digest.check(handle.finalize_hex(), &manifest.config)?;
+        info!("verified config digest against the manifest");
+    }
+
+    let config = read_config(&File::open(cfg)?)?.config;
 
     let mut ep_file = PathBuf::new();
     ep_file.push(&out_path);
@@ -221,16 +962,324 @@ fn write_entrypoint(
 
     let mut w = BufWriter::new(File::create(&ep_file)?);
 
+    match format {
+        EntrypointFormat::Script => write_entrypoint_script(&mut w, &config)?,
+        EntrypointFormat::Env => write_entrypoint_env(&mut w, &config)?,
+        EntrypointFormat::Json => write_entrypoint_json(&mut w, &config)?,
+    }
+
+    fs::set_permissions(&ep_file, fs::Permissions::from_mode(0o755))?;
+
+    Ok(())
+}
+
+fn write_entrypoint_script(w: &mut impl Write, config: &Config) -> Result<()> {
     writeln!(w, "#!/bin/bash")?;
-    for env in config.config.env.iter() {
-        writeln!(w, "{env}")?;
+
+    for env in config.env.iter() {
+        writeln!(w, "export {}", shell_quote_env(env)?)?;
+    }
+
+    if !config.working_dir.is_empty() {
+        writeln!(w, "cd {}", shell_quote(&config.working_dir))?;
     }
-    writeln!(w, "cd {}", config.config.working_dir)?;
-    for cmd in config.config.cmd.iter() {
-        writeln!(w, "{cmd}")?;
+
+    let command = config.run_command();
+    if command.is_empty() {
+        bail!("image config has no Entrypoint or Cmd to run");
     }
+    let command = command
+        .iter()
+        .map(|arg| shell_quote(arg))
+        .collect::<Vec<_>>()
+        .join(" ");
 
-    fs::set_permissions(&ep_file, fs::Permissions::from_mode(0o755))?;
+    if !config.user.is_empty() {
+        let (user, group) = config
+            .user
+            .split_once(':')
+            .map_or((config.user.as_str(), None), |(user, group)| (user, Some(group)));
+        if let Some(group) = group {
+            debug!("USER specifies group {group}, but su has no direct group-switch equivalent - dropping it");
+        }
+        writeln!(w, "exec su -c {} {}", shell_quote(&command), shell_quote(user))?;
+    } else {
+        writeln!(w, "exec {command}")?;
+    }
 
     Ok(())
 }
+
+fn write_entrypoint_env(w: &mut impl Write, config: &Config) -> Result<()> {
+    for env in config.env.iter() {
+        writeln!(w, "export {}", shell_quote_env(env)?)?;
+    }
+    Ok(())
+}
+
+fn write_entrypoint_json(w: &mut impl Write, config: &Config) -> Result<()> {
+    let env: HashMap<&str, &str> = config
+        .env
+        .iter()
+        .filter_map(|entry| entry.split_once('='))
+        .collect();
+
+    let summary = serde_json::json!({
+        "command": config.run_command(),
+        "env": env,
+        "user": config.user,
+        "working_dir": config.working_dir,
+        "exposed_ports": config.exposed_ports.keys().collect::<Vec<_>>(),
+    });
+
+    Ok(serde_json::to_writer_pretty(w, &summary)?)
+}
+
+/// Shell-quotes a `KEY=VALUE` image config env entry for use in an `export` statement, quoting
+/// the value half so the generated line stays a valid assignment (`KEY=quoted-value`) rather
+/// than `quoted-entire-thing`, which `export` wouldn't parse as an assignment. The key is never
+/// attacker-supplied shell syntax by construction: it's rejected unless it's a valid shell
+/// identifier, since unlike the value, it can't be quoted without stopping being a key.
+fn shell_quote_env(entry: &str) -> Result<String> {
+    let (key, value) = entry
+        .split_once('=')
+        .ok_or_else(|| anyhow::anyhow!("malformed env entry (missing '='): {entry}"))?;
+
+    if !is_shell_identifier(key) {
+        bail!("env entry has a key that isn't a valid shell identifier: {entry}");
+    }
+
+    Ok(format!("{key}={}", shell_quote(value)))
+}
+
+/// Whether `name` is safe to use unquoted as a shell variable name: starts with a letter or
+/// underscore, and otherwise contains only letters, digits, and underscores.
+fn is_shell_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Shell-quotes a single exec-form argument so it round-trips through `/bin/bash` unchanged,
+/// even if it contains spaces, quotes, or other special characters.
+fn shell_quote(arg: &str) -> String {
+    if !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:@%+=,".contains(c))
+    {
+        return arg.to_string();
+    }
+
+    let mut quoted = String::with_capacity(arg.len() + 2);
+    quoted.push('\'');
+    for c in arg.chars() {
+        if c == '\'' {
+            quoted.push_str("'\\''");
+        } else {
+            quoted.push(c);
+        }
+    }
+    quoted.push('\'');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Regression test for the `--verify`/`-e` combination: `write_entrypoint`'s verify block
+    /// reads the config through a `DigestReader<BufReader<File>>` wrapping a `DigestHandle`
+    /// (the API `DigestReader`/`DigestHandle` settled on once decompression needed to own the
+    /// reader), and must be able to read the handle back out via `finalize_hex` afterwards.
+    #[test]
+    fn write_entrypoint_verifies_config_digest() {
+        let src = tempdir::TempDir::new("dext-test-src").unwrap();
+        let out = tempdir::TempDir::new("dext-test-out").unwrap();
+
+        let config_json = br#"{"config":{"Cmd":["/bin/true"]}}"#;
+        let config_name = format!("{}.json", hex::encode(Sha256::digest(config_json)));
+        fs::write(src.path().join(&config_name), config_json).unwrap();
+
+        let manifest = Manifest {
+            config: config_name,
+            layers: vec![],
+        };
+
+        write_entrypoint(
+            &manifest,
+            src.path(),
+            out.path(),
+            "entrypoint.sh".to_string(),
+            true,
+            EntrypointFormat::Script,
+        )
+        .unwrap();
+
+        let written = fs::read_to_string(out.path().join("entrypoint.sh")).unwrap();
+        assert!(written.contains("/bin/true"));
+    }
+
+    #[test]
+    fn write_entrypoint_script_drops_group_from_su_target() {
+        let config = Config {
+            cmd: Some(vec!["/bin/true".to_string()]),
+            user: "nobody:nogroup".to_string(),
+            ..Default::default()
+        };
+
+        let mut script = Vec::new();
+        write_entrypoint_script(&mut script, &config).unwrap();
+        let script = String::from_utf8(script).unwrap();
+
+        assert!(script.contains("su -c /bin/true nobody"));
+        assert!(!script.contains("nogroup"));
+    }
+
+    #[test]
+    fn shell_quote_leaves_safe_tokens_alone() {
+        assert_eq!(shell_quote("foo-bar.baz:1.2_3/qux"), "foo-bar.baz:1.2_3/qux");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[test]
+    fn shell_quote_escapes_special_characters() {
+        assert_eq!(shell_quote("hello world"), "'hello world'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        assert_eq!(shell_quote("$(rm -rf /)"), "'$(rm -rf /)'");
+    }
+
+    #[test]
+    fn shell_quote_env_only_quotes_the_value() {
+        assert_eq!(shell_quote_env("PATH=/usr/bin").unwrap(), "PATH=/usr/bin");
+        assert_eq!(
+            shell_quote_env("JAVA_OPTS=-Xmx512m -Dfoo=bar").unwrap(),
+            "JAVA_OPTS='-Xmx512m -Dfoo=bar'"
+        );
+    }
+
+    #[test]
+    fn shell_quote_env_rejects_unsafe_keys() {
+        assert!(shell_quote_env("$(touch /tmp/pwned)=x").is_err());
+        assert!(shell_quote_env("FOO BAR=x").is_err());
+        assert!(shell_quote_env("no-equals-sign").is_err());
+    }
+
+    #[test]
+    fn registry_for_picks_explicit_host_over_docker_hub() {
+        assert_eq!(registry_for("ubuntu"), DEFAULT_REGISTRY);
+        assert_eq!(registry_for("library/ubuntu"), DEFAULT_REGISTRY);
+        assert_eq!(registry_for("registry.example.com/foo/bar"), "registry.example.com");
+        assert_eq!(registry_for("localhost:5000/foo"), "localhost:5000");
+        assert_eq!(registry_for("localhost/foo"), "localhost");
+    }
+
+    #[test]
+    fn compression_sniff_detects_each_magic() {
+        assert_eq!(Compression::sniff(&[0x1f, 0x8b, 0x08]), Compression::Gzip);
+        assert_eq!(Compression::sniff(&[0x28, 0xb5, 0x2f, 0xfd]), Compression::Zstd);
+        assert_eq!(
+            Compression::sniff(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]),
+            Compression::Xz
+        );
+        assert_eq!(Compression::sniff(b"BZh91AY"), Compression::Bzip2);
+        assert_eq!(Compression::sniff(b"plain tar bytes"), Compression::None);
+    }
+
+    #[test]
+    fn select_platform_descriptor_picks_the_matching_host_platform() {
+        let descriptor = |os: &str, arch: &str, digest: &str| OciDescriptor {
+            digest: digest.to_string(),
+            platform: Some(OciPlatform {
+                architecture: arch.to_string(),
+                os: os.to_string(),
+            }),
+        };
+        let manifests = vec![
+            descriptor("plan9", "arm64", "sha256:arm"),
+            descriptor(goos(), goarch(), "sha256:host"),
+            descriptor("windows", "amd64", "sha256:windows"),
+        ];
+
+        assert_eq!(select_platform_descriptor(&manifests).unwrap().digest, "sha256:host");
+    }
+
+    #[test]
+    fn select_platform_descriptor_errors_when_no_platform_matches() {
+        let manifests = vec![OciDescriptor {
+            digest: "sha256:other".to_string(),
+            platform: Some(OciPlatform {
+                architecture: "mips".to_string(),
+                os: "plan9".to_string(),
+            }),
+        }];
+
+        assert!(select_platform_descriptor(&manifests).is_err());
+    }
+
+    #[test]
+    fn oci_blob_path_rejects_non_hex_digests() {
+        assert_eq!(oci_blob_path("sha256:abc123").unwrap(), "blobs/sha256/abc123");
+        assert!(oci_blob_path("sha256:../../../../etc/passwd").is_err());
+        assert!(oci_blob_path("sha256:").is_err());
+        assert!(oci_blob_path("not-a-digest").is_err());
+    }
+
+    #[test]
+    fn blob_digest_from_legacy_docker_save_layout() {
+        let hex = "a".repeat(64);
+        let digest = BlobDigest::from_blob_name(&format!("{hex}/layer.tar")).unwrap();
+        assert_eq!(digest.algo.name(), "sha256");
+        assert_eq!(digest.expected_hex, hex);
+
+        let digest = BlobDigest::from_blob_name(&format!("{hex}.json")).unwrap();
+        assert_eq!(digest.expected_hex, hex);
+    }
+
+    #[test]
+    fn blob_digest_from_oci_image_layout() {
+        let hex = "b".repeat(128);
+        let digest = BlobDigest::from_blob_name(&format!("blobs/sha512/{hex}")).unwrap();
+        assert_eq!(digest.algo.name(), "sha512");
+        assert_eq!(digest.expected_hex, hex);
+    }
+
+    #[test]
+    fn classify_whiteout_distinguishes_opaque_remove_and_plain() {
+        assert_eq!(
+            classify_whiteout(Path::new("a/b/.wh..wh..opq")),
+            WhiteoutAction::ClearDirectory(PathBuf::from("a/b"))
+        );
+        assert_eq!(
+            classify_whiteout(Path::new("a/b/.wh.deleted")),
+            WhiteoutAction::Remove(PathBuf::from("a/b/deleted"))
+        );
+        assert_eq!(
+            classify_whiteout(Path::new("a/b/regular-file")),
+            WhiteoutAction::Extract
+        );
+    }
+
+    #[test]
+    fn resolve_within_joins_safe_relative_paths() {
+        let out_path = Path::new("/out");
+        assert_eq!(
+            resolve_within(out_path, Path::new("a/b")).unwrap(),
+            PathBuf::from("/out/a/b")
+        );
+        assert_eq!(resolve_within(out_path, Path::new("")).unwrap(), out_path);
+    }
+
+    #[test]
+    fn resolve_within_rejects_absolute_paths() {
+        let out_path = Path::new("/out");
+        assert!(resolve_within(out_path, Path::new("/etc")).is_err());
+    }
+
+    #[test]
+    fn resolve_within_rejects_parent_dir_traversal() {
+        let out_path = Path::new("/out");
+        assert!(resolve_within(out_path, Path::new("../../../home/x")).is_err());
+        assert!(resolve_within(out_path, Path::new("a/../../b")).is_err());
+    }
+}